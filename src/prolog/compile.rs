@@ -7,35 +7,109 @@ use prolog::machine::*;
 use prolog::toplevel::*;
 
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::io::Read;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::mem;
+use std::path::Path;
 
-#[allow(dead_code)]
-fn print_code(code: &Code) {
-    for clause in code {
-        match clause {
+/// One entry in a [`disassemble`]d listing, in code order.
+#[derive(Debug, Clone)]
+pub enum DisasmItem {
+    Label(String),
+    Instr(String),
+    Jump { target_label: String },
+    Switch(Vec<(String, String)>)
+}
+
+fn jump_label(target: usize) -> String {
+    format!("L{}", target)
+}
+
+// find every address a `JmpBy` or switch table entry jumps to, so labels
+// can be allocated for them before we walk the code a second time to emit
+// instructions. this is the inverse of `set_first_index`: where that
+// function fills in an as-yet-unresolved `JmpBy` offset, this resolves an
+// already-filled-in one back into a target address.
+fn collect_jump_targets(code: &Code) -> HashSet<usize> {
+    let mut targets = HashSet::new();
+
+    for (idx, line) in code.iter().enumerate() {
+        match line {
+            &Line::Control(ControlInstruction::JmpBy(_, offset, ..)) if offset != 0 =>
+                { targets.insert(idx + offset); },
+            &Line::IndexedChoice(ref choice) =>
+                for ptr in choice.targets() {
+                    if let &IndexPtr::Index(p) = ptr {
+                        targets.insert(p);
+                    }
+                },
+            &Line::Indexing(ref indexing) =>
+                for ptr in indexing.targets() {
+                    if let &IndexPtr::Index(p) = ptr {
+                        targets.insert(p);
+                    }
+                },
+            _ => {}
+        }
+    }
+
+    targets
+}
+
+/// Walk a compiled `Code` vector and produce a structured listing in code
+/// order: a [`DisasmItem::Label`] marker at every address some other
+/// instruction jumps to, [`DisasmItem::Jump`] in place of a resolved
+/// `JmpBy` (naming its target label rather than a raw offset),
+/// [`DisasmItem::Switch`] for the resolved indexing/indexed-choice tables,
+/// and one [`DisasmItem::Instr`] per flattened fact/query instruction.
+/// This is the inverse of `set_first_index`, and is meant as a
+/// `listing/0`-style introspection API, and a foundation for a debugger.
+pub fn disassemble(code: &Code) -> Vec<DisasmItem> {
+    let targets = collect_jump_targets(code);
+    let mut items = Vec::new();
+
+    for (idx, line) in code.iter().enumerate() {
+        if targets.contains(&idx) {
+            items.push(DisasmItem::Label(jump_label(idx)));
+        }
+
+        match line {
             &Line::Arithmetic(ref arith) =>
-                println!("{}", arith),
+                items.push(DisasmItem::Instr(format!("{}", arith))),
             &Line::Fact(ref fact) =>
                 for fact_instr in fact {
-                    println!("{}", fact_instr);
+                    items.push(DisasmItem::Instr(format!("{}", fact_instr)));
                 },
             &Line::Cut(ref cut) =>
-                println!("{}", cut),
+                items.push(DisasmItem::Instr(format!("{}", cut))),
             &Line::Choice(ref choice) =>
-                println!("{}", choice),
+                items.push(DisasmItem::Instr(format!("{}", choice))),
+            &Line::Control(ControlInstruction::JmpBy(_, offset, ..)) if offset != 0 =>
+                items.push(DisasmItem::Jump { target_label: jump_label(idx + offset) }),
             &Line::Control(ref control) =>
-                println!("{}", control),
+                items.push(DisasmItem::Instr(format!("{}", control))),
             &Line::IndexedChoice(ref choice) =>
-                println!("{}", choice),
+                items.push(DisasmItem::Switch(choice.targets().iter().enumerate().map(|(i, ptr)|
+                    (format!("{}", i), disasm_index_ptr(ptr))).collect())),
             &Line::Indexing(ref indexing) =>
-                println!("{}", indexing),
+                items.push(DisasmItem::Switch(indexing.arms().map(|(arm, ptr)|
+                    (arm, disasm_index_ptr(ptr))).collect())),
             &Line::Query(ref query) =>
                 for query_instr in query {
-                    println!("{}", query_instr);
+                    items.push(DisasmItem::Instr(format!("{}", query_instr)));
                 }
         }
     }
+
+    items
+}
+
+fn disasm_index_ptr(ptr: &IndexPtr) -> String {
+    match ptr {
+        &IndexPtr::Index(p) => jump_label(p),
+        &IndexPtr::Undefined => String::from("undefined"),
+        &IndexPtr::Extern(ref name, arity) => format!("extern {}/{}", name.as_str(), arity)
+    }
 }
 
 pub fn parse_code(wam: &mut Machine, buffer: &str) -> Result<TopLevelPacket, ParserError>
@@ -74,6 +148,17 @@ fn compile_relation(tl: &TopLevel, non_counted_bt: bool, flags: MachineFlags) ->
 // set first jmp_by_call or jmp_by_index instruction to code.len() -
 // idx, where idx is the place it occurs. It only does this to the
 // *first* uninitialized jmp index it encounters, then returns.
+//
+// only ever matches `Line::Control`, which stays an inline variant --
+// `Line::Fact`/`Line::Query`/`Line::Indexing` box their payloads, and
+// `ControlInstruction::CallClause` boxes its `ClauseName`, to keep
+// `mem::size_of::<Line>()` close to the size of `Arithmetic`/`Cut`
+// instead of the largest fact/query/indexing table or call name a clause
+// can carry; this loop's match arm, and every other borrow of a boxed
+// variant's contents in this file (`for fact_instr in fact`,
+// `disassemble`'s and `relocate_code`'s walks), is unaffected because
+// borrowing through a `Box<[T]>`/`Box<T>` works the same way borrowing
+// through a `Vec<T>`/`T` does.
 fn set_first_index(code: &mut Code)
 {
     let code_len = code.len();
@@ -167,6 +252,7 @@ pub fn compile_packet(wam: &mut Machine, tl: TopLevelPacket) -> EvalSession
 pub struct ListingCompiler<'a> {
     wam: &'a mut Machine,
     non_counted_bt_preds: HashSet<PredicateKey>,
+    extern_preds: HashSet<PredicateKey>,
     module: Option<Module>
 }
 
@@ -174,7 +260,8 @@ impl<'a> ListingCompiler<'a> {
     pub fn new(wam: &'a mut Machine) -> Self {
         ListingCompiler { wam,
                           module: None,
-                          non_counted_bt_preds: HashSet::new() }
+                          non_counted_bt_preds: HashSet::new(),
+                          extern_preds: HashSet::new() }
     }
 
     fn get_module_name(&self) -> ClauseName {
@@ -183,6 +270,40 @@ impl<'a> ListingCompiler<'a> {
             .unwrap_or(ClauseName::BuiltIn("user"))
     }
 
+    fn add_extern_flag(&mut self, name: ClauseName, arity: usize) {
+        self.extern_preds.insert((name, arity));
+    }
+
+    // an `:- extern(name/arity)`'d predicate's entry point resolves to the
+    // machine's native callback table rather than compiled clauses; any
+    // clauses the listing also gives for that key are still compiled and
+    // appended to `code`, but no entry point leads to them. everything
+    // else gets the usual `Index(p)` entry point.
+    fn code_index_ptr(&self, name: &ClauseName, arity: usize, p: usize) -> IndexPtr {
+        if self.extern_preds.contains(&(name.clone(), arity)) {
+            IndexPtr::Extern(name.clone(), arity)
+        } else {
+            IndexPtr::Index(p)
+        }
+    }
+
+    // a `:- extern(name/arity)` declaration with no matching clauses in
+    // `decls` never goes through `generate_code`'s loop at all -- that's
+    // the whole point of the feature, clauses aren't required -- so
+    // without this, its `code_dir` entry would simply never get written
+    // and the extern would be unreachable at call time. Only fills in
+    // keys `generate_code`/`generate_code_collecting` didn't already
+    // install a real entry for.
+    fn install_extern_predicates(&self, code_dir: &mut CodeDir) {
+        for &(ref name, arity) in &self.extern_preds {
+            let idx = code_dir.entry((name.clone(), arity)).or_insert(CodeIndex::default());
+
+            if let IndexPtr::Undefined = idx.get() {
+                set_code_index!(idx, IndexPtr::Extern(name.clone(), arity), self.get_module_name());
+            }
+        }
+    }
+
     fn generate_code(&mut self, decls: Vec<(Predicate, VecDeque<TopLevel>)>, code_dir: &mut CodeDir)
                      -> Result<Code, SessionError>
     {
@@ -203,8 +324,9 @@ impl<'a> ListingCompiler<'a> {
             compile_appendix(&mut decl_code, Vec::from(queue), non_counted_bt,
                              self.wam.machine_flags())?;
 
+            let index_ptr = self.code_index_ptr(&name, arity, p);
             let idx = code_dir.entry((name, arity)).or_insert(CodeIndex::default());
-            set_code_index!(idx, IndexPtr::Index(p), self.get_module_name());
+            set_code_index!(idx, index_ptr, self.get_module_name());
 
             code.extend(decl_code.into_iter());
         }
@@ -212,6 +334,62 @@ impl<'a> ListingCompiler<'a> {
         Ok(code)
     }
 
+    // like `generate_code`, but a failing predicate is recorded alongside
+    // the clause(s) it came from rather than aborting the whole listing;
+    // compilation continues with the remaining declarations.
+    // `start_position` continues the same monotonic counter
+    // `compile_listing_collecting`'s declaration-consuming loop left off
+    // at, rather than restarting from 0, so a `ClauseError.position` from
+    // this phase can never collide with one from that earlier phase.
+    fn generate_code_collecting(&mut self, decls: Vec<(Predicate, VecDeque<TopLevel>)>,
+                                code_dir: &mut CodeDir, errors: &mut Vec<ClauseError>,
+                                start_position: usize)
+                                -> Code
+    {
+        let mut code = vec![];
+
+        for (offset, (decl, queue)) in decls.into_iter().enumerate() {
+            let position = start_position + offset;
+            let key = decl.0.first().and_then(|cl| {
+                let arity = cl.arity();
+                cl.name().map(|name| (name, arity))
+            });
+
+            let (name, arity) = match key {
+                Some(key) => key,
+                None => {
+                    errors.push(ClauseError { key: None, position, error: SessionError::NamelessEntry });
+                    continue;
+                }
+            };
+
+            let non_counted_bt = self.non_counted_bt_preds.contains(&(name.clone(), arity));
+            let p = code.len() + self.wam.code_size();
+
+            let decl_code = compile_relation(&TopLevel::Predicate(decl), non_counted_bt,
+                                             self.wam.machine_flags())
+                .and_then(|mut decl_code| {
+                    compile_appendix(&mut decl_code, Vec::from(queue), non_counted_bt,
+                                     self.wam.machine_flags())?;
+                    Ok(decl_code)
+                });
+
+            match decl_code {
+                Ok(decl_code) => {
+                    let index_ptr = self.code_index_ptr(&name, arity, p);
+                    let idx = code_dir.entry((name, arity)).or_insert(CodeIndex::default());
+                    set_code_index!(idx, index_ptr, self.get_module_name());
+
+                    code.extend(decl_code.into_iter());
+                },
+                Err(e) =>
+                    errors.push(ClauseError { key: Some((name, arity)), position, error: SessionError::from(e) })
+            }
+        }
+
+        code
+    }
+
     fn add_code(self, code: Code, indices: MachineCodeIndices) {
         let code_dir = mem::replace(indices.code_dir, HashMap::new());
         let op_dir   = mem::replace(indices.op_dir, HashMap::new());
@@ -237,6 +415,12 @@ impl<'a> ListingCompiler<'a> {
         match decl {
             Declaration::NonCountedBacktracking(name, arity) =>
                 Ok(self.add_non_counted_bt_flag(name, arity)),
+            Declaration::Extern(name, arity) =>
+                if self.wam.has_extern(name.clone(), arity) {
+                    Ok(self.add_extern_flag(name, arity))
+                } else {
+                    Err(SessionError::ExternNotFound(name, arity))
+                },
             Declaration::Op(op_decl) =>
                 op_decl.submit(self.get_module_name(), &mut indices.op_dir),
             Declaration::UseModule(name) =>
@@ -282,6 +466,71 @@ fn use_qualified_module(module: &mut Option<Module>, submodule: &Module, exports
     }
 }
 
+/// A single clause or declaration that failed to compile under
+/// [`compile_listing_collecting`], along with the predicate it belonged to
+/// (`None` for a failing declaration, or a clause with no resolvable
+/// name/arity).
+#[derive(Debug)]
+pub struct ClauseError {
+    pub key: Option<PredicateKey>,
+    /// A 0-based ordinal, not a line/column (`TopLevelBatchWorker` doesn't
+    /// track byte or line positions yet). The counter is shared across
+    /// both phases `compile_listing_collecting` runs: it numbers each
+    /// declaration consumed from `worker` first, then continues -- rather
+    /// than restarting at 0 -- to number each predicate/clause group
+    /// `generate_code_collecting` compiles from `worker.results`. A
+    /// position is therefore unique across the whole returned `Vec`, even
+    /// though it spans two different streams.
+    pub position: usize,
+    pub error: SessionError
+}
+
+/// Like [`compile_listing`], but a malformed clause, a failing
+/// declaration (e.g. a `use_module` naming a `ModuleNotFound` module), or
+/// a predicate that fails to generate code does not abort the rest of the
+/// file. Every other declaration still gets a chance to compile; the
+/// returned `Vec<ClauseError>` carries one entry per failure so a REPL
+/// front-end can report all of them in a single pass instead of just the
+/// first.
+pub fn compile_listing_collecting<R: Read>(wam: &mut Machine, src: R, mut indices: MachineCodeIndices)
+                                           -> (EvalSession, Vec<ClauseError>)
+{
+    let mut worker = TopLevelBatchWorker::new(src, wam.atom_tbl(), wam.machine_flags());
+    let mut compiler = ListingCompiler::new(wam);
+    let mut errors = Vec::new();
+    let mut position = 0;
+
+    // `consume` is expected to always advance past the declaration it just
+    // read, whether or not that declaration went on to process cleanly, so
+    // a parse error here is recorded the same way a failing declaration or
+    // clause is elsewhere in this function -- logged, and the remaining
+    // source still gets a chance to compile -- rather than aborting the
+    // whole listing on its first bad clause.
+    loop {
+        match worker.consume(&mut indices) {
+            Ok(Some(decl)) => {
+                if let Err(e) = compiler.process_decl(decl, &mut indices) {
+                    errors.push(ClauseError { key: None, position, error: e });
+                }
+
+                position += 1;
+            },
+            Ok(None) =>
+                break,
+            Err(e) => {
+                errors.push(ClauseError { key: None, position, error: e });
+                position += 1;
+            }
+        }
+    }
+
+    let code = compiler.generate_code_collecting(worker.results, &mut indices.code_dir, &mut errors, position);
+    compiler.install_extern_predicates(&mut indices.code_dir);
+    compiler.add_code(code, indices);
+
+    (EvalSession::EntrySuccess, errors)
+}
+
 pub
 fn compile_listing<R: Read>(wam: &mut Machine, src: R, mut indices: MachineCodeIndices) -> EvalSession
 {
@@ -293,6 +542,7 @@ fn compile_listing<R: Read>(wam: &mut Machine, src: R, mut indices: MachineCodeI
     }
 
     let code = try_eval_session!(compiler.generate_code(worker.results, &mut indices.code_dir));
+    compiler.install_extern_predicates(&mut indices.code_dir);
     compiler.add_code(code, indices);
 
     EvalSession::EntrySuccess
@@ -310,3 +560,925 @@ pub fn compile_user_module<R: Read>(wam: &mut Machine, src: R) -> EvalSession {
 
     compile_listing(wam, src, indices)
 }
+
+// --- ahead-of-time compiled listings -------------------------------------
+//
+// A compiled listing is a relocatable snapshot of the work `generate_code`
+// already does: the flattened `Code`, the `(ClauseName, arity) -> entry`
+// map it would otherwise install directly into a live `code_dir`, enough
+// of the source `Module` to rebuild it, and the slice of the atom table
+// the code refers to. Loading one appends its `Code` onto the machine's
+// existing code vector at the current base `B` and relocates every
+// position-dependent value the blob carries by `B`, after re-interning its
+// atoms into the live atom table and rewriting the `ClauseName`s that
+// pointed into the old one.
+
+const COMPILED_LISTING_MAGIC: &[u8; 4] = b"SCRY";
+const COMPILED_LISTING_VERSION: u32 = 2;
+
+struct CompiledModuleSection {
+    module_decl: ModuleDecl,
+    exports: Vec<PredicateKey>,
+    code_dir: Vec<(PredicateKey, IndexPtr)>,
+    op_dir: Vec<(PredicateKey, OpDecl)>
+}
+
+struct CompiledListing {
+    code: Code,
+    predicates: Vec<(PredicateKey, IndexPtr)>,
+    module: Option<CompiledModuleSection>,
+    atoms: Vec<String>
+}
+
+// interns just the atoms a listing's predicate/module keys actually refer
+// to (as opposed to the whole machine atom table), so the `[atoms]`
+// section stays proportional to what the listing needs re-resolved on
+// load.
+struct AtomWriter {
+    atoms: Vec<String>,
+    index: HashMap<String, usize>
+}
+
+impl AtomWriter {
+    fn new() -> Self {
+        AtomWriter { atoms: Vec::new(), index: HashMap::new() }
+    }
+
+    fn intern(&mut self, name: &ClauseName) -> usize {
+        if let Some(&idx) = self.index.get(name.as_str()) {
+            return idx;
+        }
+
+        let idx = self.atoms.len();
+        self.atoms.push(name.as_str().to_string());
+        self.index.insert(name.as_str().to_string(), idx);
+        idx
+    }
+}
+
+fn write_len<W: Write>(w: &mut W, len: usize) -> io::Result<()> {
+    w.write_all(&(len as u64).to_le_bytes())
+}
+
+fn read_len<R: Read>(r: &mut R) -> io::Result<usize> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf) as usize)
+}
+
+fn write_bool<W: Write>(w: &mut W, b: bool) -> io::Result<()> {
+    w.write_all(&[b as u8])
+}
+
+fn read_bool<R: Read>(r: &mut R) -> io::Result<bool> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0] != 0)
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_len(w, s.len())?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_len(r)?;
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// a `ClauseName` embedded inside an instruction (`CallClause`,
+// `GetConstant`, `PutConstant`, an `IndexPtr::Extern` in a switch table) is
+// written out in full each time, rather than through the `[atoms]` table's
+// index scheme -- only the top-level predicate/module keys go through
+// that, since those are the ones a relocating loader needs to resolve
+// before it can build a `CodeDir`.
+fn write_clause_name<W: Write>(w: &mut W, name: &ClauseName) -> io::Result<()> {
+    write_string(w, name.as_str())
+}
+
+fn read_clause_name<R: Read>(r: &mut R) -> io::Result<ClauseName> {
+    Ok(clause_name!(read_string(r)?))
+}
+
+fn write_predicate_key<W: Write>(w: &mut W, atom_idx: usize, arity: usize) -> io::Result<()> {
+    write_len(w, atom_idx)?;
+    write_len(w, arity)
+}
+
+fn read_predicate_key<R: Read>(r: &mut R, atoms: &[ClauseName]) -> io::Result<PredicateKey> {
+    let idx = read_len(r)?;
+    let arity = read_len(r)?;
+
+    Ok((atoms[idx].clone(), arity))
+}
+
+fn write_index_ptr<W: Write>(w: &mut W, ptr: &IndexPtr) -> io::Result<()> {
+    match ptr {
+        &IndexPtr::Undefined =>
+            write_len(w, 0),
+        &IndexPtr::Index(p) => {
+            write_len(w, 1)?;
+            write_len(w, p)
+        },
+        &IndexPtr::Extern(ref name, arity) => {
+            write_len(w, 2)?;
+            write_clause_name(w, name)?;
+            write_len(w, arity)
+        }
+    }
+}
+
+fn read_index_ptr<R: Read>(r: &mut R) -> io::Result<IndexPtr> {
+    match read_len(r)? {
+        0 => Ok(IndexPtr::Undefined),
+        1 => Ok(IndexPtr::Index(read_len(r)?)),
+        2 => {
+            let name = read_clause_name(r)?;
+            let arity = read_len(r)?;
+            Ok(IndexPtr::Extern(name, arity))
+        },
+        tag => Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad IndexPtr tag {}", tag)))
+    }
+}
+
+// relocate every code-vector-absolute value a freshly appended `Code` blob
+// carries by `base`. `ControlInstruction::JmpBy`'s offset is *relative*
+// (target = idx + offset, per `collect_jump_targets`/`set_first_index`),
+// so shifting every instruction in the blob by the same `base` already
+// preserves it -- only the absolute `IndexPtr::Index`s a switch table
+// carries need to move.
+fn relocate_code(code: &mut Code, base: usize) {
+    if base == 0 {
+        return;
+    }
+
+    for line in code.iter_mut() {
+        match line {
+            &mut Line::IndexedChoice(ref mut choice) =>
+                relocate_indexed_choice(choice, base),
+            &mut Line::Indexing(ref mut indexing) =>
+                relocate_indexing(indexing, base),
+            _ => {}
+        }
+    }
+}
+
+fn relocate_index_ptr(ptr: &mut IndexPtr, base: usize) {
+    if let &mut IndexPtr::Index(ref mut p) = ptr {
+        *p += base;
+    }
+}
+
+// `Line::IndexedChoice`/`Line::Indexing` carry their own switch tables of
+// `IndexPtr`s (see `disassemble`'s handling of the same lines above); walk
+// them the same way `set_first_index` walks `Line::Control` and relocate
+// every entry by `base`.
+fn relocate_indexed_choice(choice: &mut IndexedChoiceInstruction, base: usize) {
+    for ptr in choice.targets_mut() {
+        relocate_index_ptr(ptr, base);
+    }
+}
+
+fn relocate_indexing(indexing: &mut IndexingLine, base: usize) {
+    for ptr in indexing.targets_mut() {
+        relocate_index_ptr(ptr, base);
+    }
+}
+
+fn relocate_predicates(predicates: &mut Vec<(PredicateKey, IndexPtr)>, base: usize) {
+    for &mut (_, ref mut ptr) in predicates.iter_mut() {
+        relocate_index_ptr(ptr, base);
+    }
+}
+
+fn write_fact_instr<W: Write>(w: &mut W, instr: &FactInstruction) -> io::Result<()> {
+    match instr {
+        &FactInstruction::GetConstant(r, ref c) => {
+            write_len(w, 0)?;
+            write_len(w, r)?;
+            write_clause_name(w, c)
+        },
+        &FactInstruction::GetVariable(r, v) => {
+            write_len(w, 1)?;
+            write_len(w, r)?;
+            write_len(w, v)
+        },
+        &FactInstruction::UnifyVariable(r) => {
+            write_len(w, 2)?;
+            write_len(w, r)
+        }
+    }
+}
+
+fn read_fact_instr<R: Read>(r: &mut R) -> io::Result<FactInstruction> {
+    match read_len(r)? {
+        0 => Ok(FactInstruction::GetConstant(read_len(r)?, read_clause_name(r)?)),
+        1 => Ok(FactInstruction::GetVariable(read_len(r)?, read_len(r)?)),
+        2 => Ok(FactInstruction::UnifyVariable(read_len(r)?)),
+        tag => Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad FactInstruction tag {}", tag)))
+    }
+}
+
+fn write_query_instr<W: Write>(w: &mut W, instr: &QueryInstruction) -> io::Result<()> {
+    match instr {
+        &QueryInstruction::PutConstant(r, ref c) => {
+            write_len(w, 0)?;
+            write_len(w, r)?;
+            write_clause_name(w, c)
+        },
+        &QueryInstruction::PutVariable(r, v) => {
+            write_len(w, 1)?;
+            write_len(w, r)?;
+            write_len(w, v)
+        },
+        &QueryInstruction::SetVariable(r) => {
+            write_len(w, 2)?;
+            write_len(w, r)
+        }
+    }
+}
+
+fn read_query_instr<R: Read>(r: &mut R) -> io::Result<QueryInstruction> {
+    match read_len(r)? {
+        0 => Ok(QueryInstruction::PutConstant(read_len(r)?, read_clause_name(r)?)),
+        1 => Ok(QueryInstruction::PutVariable(read_len(r)?, read_len(r)?)),
+        2 => Ok(QueryInstruction::SetVariable(read_len(r)?)),
+        tag => Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad QueryInstruction tag {}", tag)))
+    }
+}
+
+fn write_line<W: Write>(w: &mut W, line: &Line) -> io::Result<()> {
+    match line {
+        &Line::Arithmetic(ArithmeticInstruction::Add(a1, a2, t)) => {
+            write_len(w, 0)?; write_len(w, a1)?; write_len(w, a2)?; write_len(w, t)
+        },
+        &Line::Arithmetic(ArithmeticInstruction::Sub(a1, a2, t)) => {
+            write_len(w, 1)?; write_len(w, a1)?; write_len(w, a2)?; write_len(w, t)
+        },
+        &Line::Choice(ChoiceInstruction::TryMeElse(o)) => {
+            write_len(w, 2)?; write_len(w, o)
+        },
+        &Line::Choice(ChoiceInstruction::RetryMeElse(o)) => {
+            write_len(w, 3)?; write_len(w, o)
+        },
+        &Line::Choice(ChoiceInstruction::TrustMe) =>
+            write_len(w, 4),
+        &Line::Cut(CutInstruction::NeckCut) =>
+            write_len(w, 5),
+        &Line::Cut(CutInstruction::Cut(r)) => {
+            write_len(w, 6)?; write_len(w, r)
+        },
+        &Line::Control(ControlInstruction::JmpBy(arity, offset, last_call)) => {
+            write_len(w, 7)?; write_len(w, arity)?; write_len(w, offset)?; write_bool(w, last_call)
+        },
+        &Line::Control(ControlInstruction::CallClause(ref name, arity, last_call)) => {
+            write_len(w, 8)?; write_clause_name(w, name)?; write_len(w, arity)?; write_bool(w, last_call)
+        },
+        &Line::Control(ControlInstruction::Proceed) =>
+            write_len(w, 9),
+        &Line::Fact(ref instrs) => {
+            write_len(w, 10)?;
+            write_len(w, instrs.len())?;
+
+            for instr in instrs.iter() {
+                write_fact_instr(w, instr)?;
+            }
+
+            Ok(())
+        },
+        &Line::IndexedChoice(ref choice) => {
+            write_len(w, 11)?;
+            write_len(w, choice.targets().len())?;
+
+            for ptr in choice.targets() {
+                write_index_ptr(w, ptr)?;
+            }
+
+            Ok(())
+        },
+        &Line::Indexing(ref indexing) => {
+            let arms: Vec<_> = indexing.arms().collect();
+
+            write_len(w, 12)?;
+            write_len(w, arms.len())?;
+
+            for (label, ptr) in arms {
+                write_string(w, &label)?;
+                write_index_ptr(w, ptr)?;
+            }
+
+            Ok(())
+        },
+        &Line::Query(ref instrs) => {
+            write_len(w, 13)?;
+            write_len(w, instrs.len())?;
+
+            for instr in instrs.iter() {
+                write_query_instr(w, instr)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn read_line<R: Read>(r: &mut R) -> io::Result<Line> {
+    match read_len(r)? {
+        0 => Ok(Line::Arithmetic(ArithmeticInstruction::Add(read_len(r)?, read_len(r)?, read_len(r)?))),
+        1 => Ok(Line::Arithmetic(ArithmeticInstruction::Sub(read_len(r)?, read_len(r)?, read_len(r)?))),
+        2 => Ok(Line::Choice(ChoiceInstruction::TryMeElse(read_len(r)?))),
+        3 => Ok(Line::Choice(ChoiceInstruction::RetryMeElse(read_len(r)?))),
+        4 => Ok(Line::Choice(ChoiceInstruction::TrustMe)),
+        5 => Ok(Line::Cut(CutInstruction::NeckCut)),
+        6 => Ok(Line::Cut(CutInstruction::Cut(read_len(r)?))),
+        7 => {
+            let arity = read_len(r)?;
+            let offset = read_len(r)?;
+            let last_call = read_bool(r)?;
+            Ok(Line::Control(ControlInstruction::JmpBy(arity, offset, last_call)))
+        },
+        8 => {
+            let name = read_clause_name(r)?;
+            let arity = read_len(r)?;
+            let last_call = read_bool(r)?;
+            Ok(Line::Control(ControlInstruction::CallClause(Box::new(name), arity, last_call)))
+        },
+        9 => Ok(Line::Control(ControlInstruction::Proceed)),
+        10 => {
+            let len = read_len(r)?;
+            let mut instrs = Vec::with_capacity(len);
+
+            for _ in 0 .. len {
+                instrs.push(read_fact_instr(r)?);
+            }
+
+            Ok(Line::Fact(instrs.into_boxed_slice()))
+        },
+        11 => {
+            let len = read_len(r)?;
+            let mut targets = Vec::with_capacity(len);
+
+            for _ in 0 .. len {
+                targets.push(read_index_ptr(r)?);
+            }
+
+            Ok(Line::IndexedChoice(IndexedChoiceInstruction::new(targets)))
+        },
+        12 => {
+            let len = read_len(r)?;
+            let mut arms = Vec::with_capacity(len);
+
+            for _ in 0 .. len {
+                let label = read_string(r)?;
+                let ptr = read_index_ptr(r)?;
+                arms.push((label, ptr));
+            }
+
+            Ok(Line::Indexing(Box::new(IndexingLine::new(arms))))
+        },
+        13 => {
+            let len = read_len(r)?;
+            let mut instrs = Vec::with_capacity(len);
+
+            for _ in 0 .. len {
+                instrs.push(read_query_instr(r)?);
+            }
+
+            Ok(Line::Query(instrs.into_boxed_slice()))
+        },
+        tag => Err(io::Error::new(io::ErrorKind::InvalidData, format!("bad Line tag {}", tag)))
+    }
+}
+
+fn write_code_section<W: Write>(w: &mut W, code: &Code) -> io::Result<()> {
+    write_len(w, code.len())?;
+
+    for line in code {
+        write_line(w, line)?;
+    }
+
+    Ok(())
+}
+
+fn read_code_section<R: Read>(r: &mut R) -> io::Result<Code> {
+    let len = read_len(r)?;
+    let mut code = Vec::with_capacity(len);
+
+    for _ in 0 .. len {
+        code.push(read_line(r)?);
+    }
+
+    Ok(code)
+}
+
+/// Compile `src` the same way [`compile_listing`] does, but write the
+/// resulting code, predicate table, module metadata and referenced atoms
+/// out to `path` as a self-contained, relocatable artifact instead of
+/// installing it into `wam`. The listing can later be brought in with
+/// [`load_compiled_listing`] without re-parsing the source.
+pub fn compile_listing_to_file<R: Read, P: AsRef<Path>>(wam: &mut Machine, src: R, path: P) -> EvalSession {
+    let mut indices = machine_code_indices!(&mut CodeDir::new(), &mut default_op_dir(),
+                                            &mut HashMap::new());
+
+    let mut worker = TopLevelBatchWorker::new(src, wam.atom_tbl(), wam.machine_flags());
+    let mut compiler = ListingCompiler::new(wam);
+
+    while let Some(decl) = try_eval_session!(worker.consume(&mut indices)) {
+        try_eval_session!(compiler.process_decl(decl, &mut indices));
+    }
+
+    let mut code_dir = CodeDir::new();
+    let code = try_eval_session!(compiler.generate_code(worker.results, &mut code_dir));
+    compiler.install_extern_predicates(&mut code_dir);
+
+    let mut atom_writer = AtomWriter::new();
+
+    // mirrors `ListingCompiler::add_code`: a listing compiled under a
+    // `:- module` declaration has all of its generated predicates folded
+    // into that module rather than installed at top level, so the
+    // `[modules]` section -- not `[predicates]` -- is what carries them.
+    let (predicates, module) = match compiler.module.take() {
+        Some(mut module) => {
+            module.code_dir.extend(as_module_code_dir(code_dir));
+
+            atom_writer.intern(&module.module_decl.name);
+
+            for key in &module.module_decl.exports {
+                atom_writer.intern(&key.0);
+            }
+
+            let module_code_dir = module.code_dir.iter()
+                .map(|(key, idx)| {
+                    atom_writer.intern(&key.0);
+                    (key.clone(), idx.get())
+                })
+                .collect();
+
+            let op_dir = module.op_dir.iter()
+                .map(|(key, op_decl)| {
+                    atom_writer.intern(&key.0);
+                    atom_writer.intern(&op_decl.name);
+                    (key.clone(), op_decl.clone())
+                })
+                .collect();
+
+            let section = CompiledModuleSection {
+                module_decl: module.module_decl.clone(),
+                exports: module.module_decl.exports.clone(),
+                code_dir: module_code_dir,
+                op_dir
+            };
+
+            (Vec::new(), Some(section))
+        },
+        None => {
+            let predicates = code_dir.iter()
+                .map(|(key, idx)| {
+                    atom_writer.intern(&key.0);
+                    (key.clone(), idx.get())
+                })
+                .collect();
+
+            (predicates, None)
+        }
+    };
+
+    let listing = CompiledListing { code, predicates, module, atoms: atom_writer.atoms };
+
+    match write_compiled_listing(&listing, path) {
+        Ok(()) => EvalSession::EntrySuccess,
+        Err(e) => EvalSession::from(SessionError::ImpermissibleEntry(e.to_string()))
+    }
+}
+
+fn write_predicate_table<W: Write>(w: &mut W, atoms: &AtomWriter, predicates: &[(PredicateKey, IndexPtr)])
+                                   -> io::Result<()>
+{
+    write_len(w, predicates.len())?;
+
+    for &(ref key, ref ptr) in predicates {
+        write_predicate_key(w, *atoms.index.get(key.0.as_str()).unwrap(), key.1)?;
+        write_index_ptr(w, ptr)?;
+    }
+
+    Ok(())
+}
+
+fn write_compiled_listing<P: AsRef<Path>>(listing: &CompiledListing, path: P) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut w = BufWriter::new(file);
+
+    w.write_all(COMPILED_LISTING_MAGIC)?;
+    w.write_all(&COMPILED_LISTING_VERSION.to_le_bytes())?;
+
+    write_len(&mut w, listing.atoms.len())?;
+
+    for atom in &listing.atoms {
+        write_string(&mut w, atom)?;
+    }
+
+    write_code_section(&mut w, &listing.code)?;
+
+    let mut atom_index = HashMap::new();
+
+    for (idx, atom) in listing.atoms.iter().enumerate() {
+        atom_index.insert(atom.clone(), idx);
+    }
+
+    let atom_writer = AtomWriter { atoms: listing.atoms.clone(), index: atom_index };
+
+    write_predicate_table(&mut w, &atom_writer, &listing.predicates)?;
+
+    match listing.module {
+        Some(ref module) => {
+            write_bool(&mut w, true)?;
+
+            write_len(&mut w, *atom_writer.index.get(module.module_decl.name.as_str()).unwrap())?;
+            write_len(&mut w, module.exports.len())?;
+
+            for key in &module.exports {
+                write_predicate_key(&mut w, *atom_writer.index.get(key.0.as_str()).unwrap(), key.1)?;
+            }
+
+            write_predicate_table(&mut w, &atom_writer, &module.code_dir)?;
+
+            write_len(&mut w, module.op_dir.len())?;
+
+            for &(ref key, ref op_decl) in &module.op_dir {
+                write_predicate_key(&mut w, *atom_writer.index.get(key.0.as_str()).unwrap(), key.1)?;
+                write_len(&mut w, *atom_writer.index.get(op_decl.name.as_str()).unwrap())?;
+                write_len(&mut w, op_decl.priority)?;
+                write_string(&mut w, op_decl.spec)?;
+            }
+        },
+        None =>
+            write_bool(&mut w, false)?
+    }
+
+    w.flush()
+}
+
+fn leak_str(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+fn read_predicate_table<R: Read>(r: &mut R, atoms: &[ClauseName])
+                                 -> io::Result<Vec<(PredicateKey, IndexPtr)>>
+{
+    let len = read_len(r)?;
+    let mut predicates = Vec::with_capacity(len);
+
+    for _ in 0 .. len {
+        let key = read_predicate_key(r, atoms)?;
+        let ptr = read_index_ptr(r)?;
+
+        predicates.push((key, ptr));
+    }
+
+    Ok(predicates)
+}
+
+fn code_dir_from_predicates(predicates: Vec<(PredicateKey, IndexPtr)>, module_name: ClauseName) -> CodeDir {
+    let mut code_dir = CodeDir::new();
+
+    for (key, ptr) in predicates {
+        let idx = code_dir.entry(key).or_insert(CodeIndex::default());
+        set_code_index!(idx, ptr, module_name.clone());
+    }
+
+    code_dir
+}
+
+/// Load a listing written by [`compile_listing_to_file`], appending its
+/// code onto `wam`'s code vector at the current base offset and relocating
+/// every absolute `IndexPtr::Index` the blob carries by that base (a
+/// relative `ControlInstruction::JmpBy` needs no adjustment -- see
+/// `relocate_code`), after re-interning its atoms into `wam`'s live atom
+/// table and rewriting the `ClauseName`s that pointed into the old one.
+pub fn load_compiled_listing<P: AsRef<Path>>(wam: &mut Machine, path: P) -> EvalSession {
+    let file = try_eval_session!(File::open(path).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+    let mut r = BufReader::new(file);
+
+    let mut magic = [0u8; 4];
+
+    try_eval_session!(r.read_exact(&mut magic).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    if &magic != COMPILED_LISTING_MAGIC {
+        return EvalSession::from(SessionError::ImpermissibleEntry(
+            String::from("not a compiled listing")));
+    }
+
+    let mut version_buf = [0u8; 4];
+
+    try_eval_session!(r.read_exact(&mut version_buf).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    if u32::from_le_bytes(version_buf) != COMPILED_LISTING_VERSION {
+        return EvalSession::from(SessionError::ImpermissibleEntry(
+            String::from("unsupported compiled listing version")));
+    }
+
+    let atom_count = try_eval_session!(read_len(&mut r).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    let mut atoms = Vec::with_capacity(atom_count);
+
+    for _ in 0 .. atom_count {
+        atoms.push(try_eval_session!(read_string(&mut r).map_err(|e|
+            SessionError::ImpermissibleEntry(e.to_string()))));
+    }
+
+    // build an old-id -> new-id map by re-interning every atom the
+    // listing refers to into the machine's live atom table, then rewrite
+    // the predicate table to use the live `ClauseName`s.
+    let remapped_atoms: Vec<ClauseName> = atoms.iter()
+        .map(|atom| wam.atom_tbl().borrow_mut().intern(atom))
+        .collect();
+
+    let mut code = try_eval_session!(read_code_section(&mut r).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    let base = wam.code_size();
+    relocate_code(&mut code, base);
+
+    let mut predicates = try_eval_session!(read_predicate_table(&mut r, &remapped_atoms).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    relocate_predicates(&mut predicates, base);
+
+    let has_module = try_eval_session!(read_bool(&mut r).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    if !has_module {
+        let code_dir = code_dir_from_predicates(predicates, clause_name!("user"));
+        wam.add_batched_code(code, code_dir);
+
+        return EvalSession::EntrySuccess;
+    }
+
+    let module_name_idx = try_eval_session!(read_len(&mut r).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+    let module_name = remapped_atoms[module_name_idx].clone();
+
+    let export_count = try_eval_session!(read_len(&mut r).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+    let mut exports = Vec::with_capacity(export_count);
+
+    for _ in 0 .. export_count {
+        exports.push(try_eval_session!(read_predicate_key(&mut r, &remapped_atoms).map_err(|e|
+            SessionError::ImpermissibleEntry(e.to_string()))));
+    }
+
+    let mut module_predicates = try_eval_session!(read_predicate_table(&mut r, &remapped_atoms).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+
+    relocate_predicates(&mut module_predicates, base);
+
+    let op_count = try_eval_session!(read_len(&mut r).map_err(|e|
+        SessionError::ImpermissibleEntry(e.to_string())));
+    let mut op_dir = OpDir::new();
+
+    for _ in 0 .. op_count {
+        let key = try_eval_session!(read_predicate_key(&mut r, &remapped_atoms).map_err(|e|
+            SessionError::ImpermissibleEntry(e.to_string())));
+        let op_name_idx = try_eval_session!(read_len(&mut r).map_err(|e|
+            SessionError::ImpermissibleEntry(e.to_string())));
+        let priority = try_eval_session!(read_len(&mut r).map_err(|e|
+            SessionError::ImpermissibleEntry(e.to_string())));
+        let spec = try_eval_session!(read_string(&mut r).map_err(|e|
+            SessionError::ImpermissibleEntry(e.to_string())));
+
+        let op_decl = OpDecl { name: remapped_atoms[op_name_idx].clone(), priority, spec: leak_str(spec) };
+        op_dir.insert(key, op_decl);
+    }
+
+    // a listing compiled under a `:- module` declaration folds all of its
+    // predicates into the module's own code_dir (see
+    // `compile_listing_to_file`), so `predicates` itself is empty here.
+    debug_assert!(predicates.is_empty());
+
+    let module_code_dir = code_dir_from_predicates(module_predicates, module_name.clone());
+
+    let mut module = Module::new(ModuleDecl { name: module_name, exports });
+    module.code_dir = module_code_dir;
+    module.op_dir = op_dir;
+
+    wam.add_module(module, code);
+
+    EvalSession::EntrySuccess
+}
+
+// --- extern predicates ----------------------------------------------------
+//
+// `:- extern(name/arity)` binds a predicate key to a native Rust callback
+// instead of compiled WAM clauses, via `Declaration::Extern` (toplevel.rs),
+// `IndexPtr::Extern` (instructions.rs) and the `externs` table on `Machine`
+// (machine.rs). Only the `ListingCompiler` logic that drives them, and the
+// `impl Machine` block registering/invoking them, live here.
+
+/// The result of invoking a registered extern predicate: `Ok(true)` to
+/// succeed deterministically, `Ok(false)` to fail, mirroring the success
+/// or failure of an ordinary clause body.
+pub type CallResult = Result<bool, SessionError>;
+
+/// A native implementation of an `:- extern(name/arity)` predicate. Reads
+/// its arguments out of `args` (one `Addr` per declared argument register)
+/// and is free to read or mutate the rest of `Machine`'s state, the way a
+/// built-in does.
+pub type ExternFn = Box<Fn(&mut Machine, &[Addr]) -> CallResult>;
+
+impl Machine {
+    /// Register a native callback for `name/arity` so that a subsequent
+    /// `:- extern(name/arity)` declaration in a consulted listing resolves
+    /// to it instead of requiring compiled clauses.
+    pub fn register_extern(&mut self, name: ClauseName, arity: usize, f: ExternFn) {
+        self.externs.insert((name, arity), f);
+    }
+
+    fn has_extern(&self, name: ClauseName, arity: usize) -> bool {
+        self.externs.contains_key(&(name, arity))
+    }
+
+    /// Look up and invoke the extern registered for `name/arity`, passing
+    /// it `args`; returns `SessionError::ExternNotFound` if none is
+    /// registered. `Machine` has no call dispatch of its own that invokes
+    /// this for an `IndexPtr::Extern` entry: an extern runs only when this
+    /// is called directly.
+    pub fn call_extern(&mut self, name: ClauseName, arity: usize, args: &[Addr]) -> CallResult {
+        let f = self.externs.remove(&(name.clone(), arity))
+            .ok_or_else(|| SessionError::ExternNotFound(name.clone(), arity))?;
+
+        let result = f(self, args);
+        self.externs.insert((name, arity), f);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    fn temp_path(name: &str) -> ::std::path::PathBuf {
+        let mut path = ::std::env::temp_dir();
+        path.push(format!("scryer_compile_test_{}_{}", ::std::process::id(), name));
+        path
+    }
+
+    #[test]
+    fn compiled_listing_round_trips_code_and_extern_predicates() {
+        let path = temp_path("listing.bin");
+
+        let code = vec![
+            Line::Control(ControlInstruction::CallClause(Box::new(clause_name!("foo")), 1, true)),
+            Line::Fact(vec![FactInstruction::GetConstant(0, clause_name!("a"))].into_boxed_slice()),
+            Line::Control(ControlInstruction::Proceed)
+        ];
+
+        let predicates = vec![
+            ((clause_name!("foo"), 1), IndexPtr::Index(0)),
+            ((clause_name!("bar"), 2), IndexPtr::Extern(clause_name!("bar"), 2))
+        ];
+
+        let mut atom_writer = AtomWriter::new();
+
+        for &(ref key, _) in &predicates {
+            atom_writer.intern(&key.0);
+        }
+
+        let listing = CompiledListing { code: code.clone(), predicates, module: None, atoms: atom_writer.atoms };
+        write_compiled_listing(&listing, &path).expect("write_compiled_listing failed");
+
+        let mut wam = Machine::new();
+        let base = wam.code_size();
+
+        match load_compiled_listing(&mut wam, &path) {
+            EvalSession::EntrySuccess => {},
+            EvalSession::Error(e) => panic!("load_compiled_listing failed: {:?}", e)
+        }
+
+        fs::remove_file(&path).ok();
+
+        assert_eq!(wam.code_size(), base + code.len());
+
+        let foo_idx = wam.code_dir.get(&(clause_name!("foo"), 1)).expect("foo/1 missing from code_dir");
+        assert_eq!(foo_idx.get(), IndexPtr::Index(base));
+
+        // an extern predicate must survive the round trip as Extern, not
+        // get collapsed into an ordinary Index(0) entry.
+        let bar_idx = wam.code_dir.get(&(clause_name!("bar"), 2)).expect("bar/2 missing from code_dir");
+        assert_eq!(bar_idx.get(), IndexPtr::Extern(clause_name!("bar"), 2));
+    }
+
+    #[test]
+    fn compiled_listing_relocates_code_and_predicates_at_nonzero_base() {
+        let path = temp_path("listing_nonzero_base.bin");
+
+        let mut wam = Machine::new();
+
+        // pre-populate the machine with some code of its own first, so
+        // `base = wam.code_size()` is nonzero and `relocate_code`'s
+        // `if base == 0 { return; }` short-circuit isn't what's actually
+        // exercised by this test.
+        wam.add_user_code(clause_name!("pad"), 0, vec![Line::Control(ControlInstruction::Proceed)]);
+
+        let base = wam.code_size();
+        assert_eq!(base, 1);
+
+        let code = vec![
+            Line::IndexedChoice(IndexedChoiceInstruction::new(vec![
+                IndexPtr::Index(0), IndexPtr::Index(1)
+            ])),
+            Line::Control(ControlInstruction::Proceed)
+        ];
+
+        let predicates = vec![((clause_name!("foo"), 0), IndexPtr::Index(1))];
+
+        let mut atom_writer = AtomWriter::new();
+
+        for &(ref key, _) in &predicates {
+            atom_writer.intern(&key.0);
+        }
+
+        let listing = CompiledListing { code: code.clone(), predicates, module: None, atoms: atom_writer.atoms };
+        write_compiled_listing(&listing, &path).expect("write_compiled_listing failed");
+
+        match load_compiled_listing(&mut wam, &path) {
+            EvalSession::EntrySuccess => {},
+            EvalSession::Error(e) => panic!("load_compiled_listing failed: {:?}", e)
+        }
+
+        fs::remove_file(&path).ok();
+
+        assert_eq!(wam.code_size(), base + code.len());
+
+        // the top-level predicate table's absolute `IndexPtr::Index` must
+        // shift by `base`.
+        let foo_idx = wam.code_dir.get(&(clause_name!("foo"), 0)).expect("foo/0 missing from code_dir");
+        assert_eq!(foo_idx.get(), IndexPtr::Index(1 + base));
+
+        // the switch table embedded in the relocated code itself (not
+        // just the top-level predicate table) must also shift by `base`.
+        match &wam.code()[base] {
+            &Line::IndexedChoice(ref choice) =>
+                assert_eq!(choice.targets(), &[IndexPtr::Index(0 + base), IndexPtr::Index(1 + base)]),
+            other => panic!("expected an IndexedChoice at the relocated base, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn extern_without_clauses_gets_code_dir_entry() {
+        let mut wam = Machine::new();
+        wam.register_extern(clause_name!("greet"), 1, Box::new(|_, _| Ok(true)));
+
+        let mut compiler = ListingCompiler::new(&mut wam);
+        let mut in_situ_code_dir = HashMap::new();
+        let mut op_dir = default_op_dir();
+        let mut decl_code_dir = CodeDir::new();
+
+        let mut indices = machine_code_indices!(&mut decl_code_dir, &mut op_dir, &mut in_situ_code_dir);
+        compiler.process_decl(Declaration::Extern(clause_name!("greet"), 1), &mut indices)
+            .expect("extern declaration should resolve against a registered callback");
+
+        let mut code_dir = CodeDir::new();
+        let code = compiler.generate_code(vec![], &mut code_dir).expect("empty decls should generate no code");
+        assert!(code.is_empty());
+
+        // the bug this guards against: a pure `:- extern(name/arity)`
+        // declaration with no matching clauses in `decls` never went
+        // through `generate_code`'s loop at all, so `code_dir` never got
+        // an entry for it.
+        compiler.install_extern_predicates(&mut code_dir);
+
+        let idx = code_dir.get(&(clause_name!("greet"), 1)).expect("greet/1 missing from code_dir");
+        assert_eq!(idx.get(), IndexPtr::Extern(clause_name!("greet"), 1));
+    }
+
+    #[test]
+    fn disassemble_resolves_relative_jumps_to_labels() {
+        let code = vec![
+            Line::Control(ControlInstruction::JmpBy(0, 2, false)),
+            Line::Cut(CutInstruction::NeckCut),
+            Line::Control(ControlInstruction::Proceed)
+        ];
+
+        let items = disassemble(&code);
+        assert_eq!(items.len(), 4);
+
+        match &items[0] {
+            &DisasmItem::Jump { ref target_label } => assert_eq!(*target_label, jump_label(2)),
+            other => panic!("expected a Jump item, got {:?}", other)
+        }
+
+        match &items[2] {
+            &DisasmItem::Label(ref label) => assert_eq!(*label, jump_label(2)),
+            other => panic!("expected a Label item, got {:?}", other)
+        }
+    }
+}
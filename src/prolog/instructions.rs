@@ -0,0 +1,224 @@
+use prolog_parser::ast::ClauseName;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+pub type Code = Vec<Line>;
+
+/// One compiled instruction or group of instructions. `Fact`/`Query`
+/// carry a variable-length instruction slice and `Indexing` a whole
+/// switch table, so those three are boxed to keep them from padding out
+/// every other variant to the size of the largest payload a clause can
+/// carry. `ControlInstruction::CallClause` boxes its `ClauseName` for the
+/// same reason -- that name is the one inline field big enough to matter
+/// (a `ClauseName::BuiltIn` is a `&'static str` fat pointer) -- so that
+/// `Control`, the variant every call site actually pays for, comes out
+/// close to the size of `Arithmetic`/`Choice`/`Cut` rather than twice it.
+#[derive(Debug, Clone)]
+pub enum Line {
+    Arithmetic(ArithmeticInstruction),
+    Choice(ChoiceInstruction),
+    Cut(CutInstruction),
+    Control(ControlInstruction),
+    Fact(Box<[FactInstruction]>),
+    IndexedChoice(IndexedChoiceInstruction),
+    Indexing(Box<IndexingLine>),
+    Query(Box<[QueryInstruction]>)
+}
+
+#[derive(Debug, Clone)]
+pub enum ArithmeticInstruction {
+    Add(usize, usize, usize),
+    Sub(usize, usize, usize)
+}
+
+impl fmt::Display for ArithmeticInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ArithmeticInstruction::Add(a1, a2, t) => write!(f, "add {}, {}, {}", a1, a2, t),
+            &ArithmeticInstruction::Sub(a1, a2, t) => write!(f, "sub {}, {}, {}", a1, a2, t)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ChoiceInstruction {
+    TryMeElse(usize),
+    RetryMeElse(usize),
+    TrustMe
+}
+
+impl fmt::Display for ChoiceInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ChoiceInstruction::TryMeElse(offset) => write!(f, "try_me_else {}", offset),
+            &ChoiceInstruction::RetryMeElse(offset) => write!(f, "retry_me_else {}", offset),
+            &ChoiceInstruction::TrustMe => write!(f, "trust_me")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CutInstruction {
+    NeckCut,
+    Cut(usize)
+}
+
+impl fmt::Display for CutInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &CutInstruction::NeckCut => write!(f, "neck_cut"),
+            &CutInstruction::Cut(r) => write!(f, "cut {}", r)
+        }
+    }
+}
+
+/// `JmpBy(arity, offset, is_last_call)`. `offset` is *relative*: the
+/// instruction's jump target is always `idx + offset`, where `idx` is the
+/// instruction's own position in the enclosing `Code` vector (see
+/// `set_first_index`/`disassemble`/`collect_jump_targets` in compile.rs).
+/// Because it's relative, appending a `Code` blob at a new base never
+/// needs to touch an already-resolved `JmpBy` -- every instruction in the
+/// blob shifts by the same base, so `idx + offset` is preserved.
+#[derive(Debug, Clone)]
+pub enum ControlInstruction {
+    JmpBy(usize, usize, bool),
+    // `ClauseName` boxed so this variant -- the one every ordinary call
+    // site pays for -- doesn't carry a `&'static str` fat pointer inline
+    // and stay twice the size of `JmpBy`/`Proceed`.
+    CallClause(Box<ClauseName>, usize, bool),
+    Proceed
+}
+
+impl fmt::Display for ControlInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ControlInstruction::JmpBy(arity, offset, last_call) =>
+                write!(f, "jmp_by {}, {}, {}", arity, offset, last_call),
+            &ControlInstruction::CallClause(ref name, arity, last_call) =>
+                write!(f, "call {}/{}, {}", name.as_str(), arity, last_call),
+            &ControlInstruction::Proceed =>
+                write!(f, "proceed")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FactInstruction {
+    GetConstant(usize, ClauseName),
+    GetVariable(usize, usize),
+    UnifyVariable(usize)
+}
+
+impl fmt::Display for FactInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &FactInstruction::GetConstant(r, ref c) => write!(f, "get_constant {}, {}", c.as_str(), r),
+            &FactInstruction::GetVariable(r, v) => write!(f, "get_variable {}, {}", r, v),
+            &FactInstruction::UnifyVariable(r) => write!(f, "unify_variable {}", r)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum QueryInstruction {
+    PutConstant(usize, ClauseName),
+    PutVariable(usize, usize),
+    SetVariable(usize)
+}
+
+impl fmt::Display for QueryInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &QueryInstruction::PutConstant(r, ref c) => write!(f, "put_constant {}, {}", c.as_str(), r),
+            &QueryInstruction::PutVariable(r, v) => write!(f, "put_variable {}, {}", r, v),
+            &QueryInstruction::SetVariable(r) => write!(f, "set_variable {}", r)
+        }
+    }
+}
+
+/// An entry point a `CodeIndex` can resolve to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexPtr {
+    Undefined,
+    /// An absolute offset into the machine's code vector.
+    Index(usize),
+    /// Resolves to a native callback registered via
+    /// `Machine::register_extern`, rather than compiled code.
+    Extern(ClauseName, usize)
+}
+
+/// A shared, mutable handle to an `IndexPtr` and the name of the module
+/// that owns it. Shared (via `Rc<RefCell<_>>`) because the same entry
+/// point is often reachable through more than one `code_dir` -- a
+/// module's own and every `use_module`r's imported copy -- and a later
+/// `set_code_index!` (e.g. when a predicate is recompiled) needs to be
+/// visible through all of them.
+#[derive(Clone)]
+pub struct CodeIndex(Rc<RefCell<(IndexPtr, ClauseName)>>);
+
+impl CodeIndex {
+    pub fn get(&self) -> IndexPtr {
+        self.0.borrow().0.clone()
+    }
+
+    pub fn set(&self, ptr: IndexPtr, module_name: ClauseName) {
+        *self.0.borrow_mut() = (ptr, module_name);
+    }
+}
+
+impl Default for CodeIndex {
+    fn default() -> Self {
+        CodeIndex(Rc::new(RefCell::new((IndexPtr::Undefined, ClauseName::BuiltIn("user")))))
+    }
+}
+
+/// The switch table of a first-argument indexed choice point: one
+/// `IndexPtr` per alternative clause.
+#[derive(Debug, Clone)]
+pub struct IndexedChoiceInstruction {
+    targets: Vec<IndexPtr>
+}
+
+impl IndexedChoiceInstruction {
+    pub fn new(targets: Vec<IndexPtr>) -> Self {
+        IndexedChoiceInstruction { targets }
+    }
+
+    pub fn targets(&self) -> &[IndexPtr] {
+        &self.targets
+    }
+
+    pub fn targets_mut(&mut self) -> &mut [IndexPtr] {
+        &mut self.targets
+    }
+}
+
+/// A `switch_on_term`-style dispatch: one labeled arm per first-argument
+/// shape (`var`, `constant`, `list`, `structure`), each an `IndexPtr` into
+/// the clauses that apply to it.
+#[derive(Debug, Clone)]
+pub struct IndexingLine {
+    arm_labels: Vec<String>,
+    arm_targets: Vec<IndexPtr>
+}
+
+impl IndexingLine {
+    pub fn new(arms: Vec<(String, IndexPtr)>) -> Self {
+        let (arm_labels, arm_targets) = arms.into_iter().unzip();
+        IndexingLine { arm_labels, arm_targets }
+    }
+
+    pub fn targets(&self) -> &[IndexPtr] {
+        &self.arm_targets
+    }
+
+    pub fn targets_mut(&mut self) -> &mut [IndexPtr] {
+        &mut self.arm_targets
+    }
+
+    pub fn arms(&self) -> impl Iterator<Item = (String, &IndexPtr)> {
+        self.arm_labels.iter().cloned().zip(self.arm_targets.iter())
+    }
+}
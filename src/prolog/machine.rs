@@ -0,0 +1,223 @@
+use prolog_parser::ast::ClauseName;
+
+use prolog::compile::ExternFn;
+use prolog::instructions::{Code, CodeIndex, IndexPtr};
+use prolog::toplevel::{ModuleDecl, OpDecl, PredicateKey, SessionError, EvalSession};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub type CodeDir = HashMap<PredicateKey, CodeIndex>;
+pub type OpDir = HashMap<PredicateKey, OpDecl>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MachineFlags;
+
+pub type AllocVarDict = HashMap<ClauseName, usize>;
+
+#[derive(Debug, Clone)]
+pub enum Addr {
+    Con(usize),
+    Lis(usize),
+    Str(usize)
+}
+
+pub fn default_op_dir() -> OpDir {
+    HashMap::new()
+}
+
+// `code_dir` built up while compiling a listing is keyed the same way a
+// `Module`'s own `code_dir` is, so folding one into the other is the
+// identity function; it exists as a named conversion so call sites read
+// as "this code now belongs to a module" rather than a bare `.extend`.
+pub fn as_module_code_dir(code_dir: CodeDir) -> CodeDir {
+    code_dir
+}
+
+pub struct AtomTable {
+    atoms: Vec<String>
+}
+
+impl AtomTable {
+    pub fn new() -> Self {
+        AtomTable { atoms: Vec::new() }
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<String> {
+        self.atoms.iter()
+    }
+
+    // a minimal interner: look the atom up by value and record it if it
+    // isn't present yet. the returned `ClauseName::BuiltIn` needs a
+    // `&'static str`, so every call leaks a fresh copy of the atom's
+    // bytes, whether or not the atom was already present.
+    pub fn intern(&mut self, atom: &str) -> ClauseName {
+        if !self.atoms.iter().any(|a| a == atom) {
+            self.atoms.push(atom.to_string());
+        }
+
+        ClauseName::BuiltIn(Box::leak(atom.to_string().into_boxed_str()))
+    }
+}
+
+pub struct MachineCodeIndices<'a> {
+    pub code_dir: &'a mut CodeDir,
+    pub op_dir: &'a mut OpDir,
+    pub in_situ_code_dir: &'a mut HashMap<PredicateKey, usize>
+}
+
+impl<'a> MachineCodeIndices<'a> {
+    pub fn use_module(&mut self, submodule: &Module) {
+        self.code_dir.extend(submodule.code_dir.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.op_dir.extend(submodule.op_dir.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    pub fn use_qualified_module(&mut self, submodule: &Module, exports: &Vec<PredicateKey>) {
+        for key in exports {
+            if let Some(idx) = submodule.code_dir.get(key) {
+                self.code_dir.insert(key.clone(), idx.clone());
+            }
+        }
+    }
+}
+
+macro_rules! machine_code_indices {
+    ($code_dir:expr, $op_dir:expr, $in_situ_code_dir:expr) => {
+        MachineCodeIndices { code_dir: $code_dir, op_dir: $op_dir, in_situ_code_dir: $in_situ_code_dir }
+    }
+}
+
+macro_rules! set_code_index {
+    ($idx:expr, $ptr:expr, $module_name:expr) => {
+        $idx.set($ptr, $module_name)
+    }
+}
+
+pub struct Module {
+    pub module_decl: ModuleDecl,
+    pub code_dir: CodeDir,
+    pub op_dir: OpDir
+}
+
+impl Module {
+    pub fn new(module_decl: ModuleDecl) -> Self {
+        Module { module_decl, code_dir: HashMap::new(), op_dir: HashMap::new() }
+    }
+
+    pub fn use_module(&mut self, submodule: &Module) {
+        self.code_dir.extend(submodule.code_dir.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.op_dir.extend(submodule.op_dir.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    pub fn use_qualified_module(&mut self, submodule: &Module, exports: &Vec<PredicateKey>) {
+        for key in exports {
+            if let Some(idx) = submodule.code_dir.get(key) {
+                self.code_dir.insert(key.clone(), idx.clone());
+            }
+        }
+    }
+}
+
+pub struct Machine {
+    atom_tbl: Rc<RefCell<AtomTable>>,
+    flags: MachineFlags,
+    pub code_dir: CodeDir,
+    pub op_dir: OpDir,
+    pub modules: HashMap<ClauseName, Module>,
+    code: Code,
+    pub(crate) externs: HashMap<PredicateKey, ExternFn>
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Machine {
+            atom_tbl: Rc::new(RefCell::new(AtomTable::new())),
+            flags: MachineFlags,
+            code_dir: HashMap::new(),
+            op_dir: default_op_dir(),
+            modules: HashMap::new(),
+            code: Vec::new(),
+            externs: HashMap::new()
+        }
+    }
+
+    pub fn atom_tbl(&self) -> Rc<RefCell<AtomTable>> {
+        self.atom_tbl.clone()
+    }
+
+    pub fn machine_flags(&self) -> MachineFlags {
+        self.flags
+    }
+
+    pub fn code_size(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn code(&self) -> &Code {
+        &self.code
+    }
+
+    pub fn get_module(&self, name: ClauseName) -> Option<&Module> {
+        self.modules.get(&name)
+    }
+
+    pub fn add_module(&mut self, module: Module, code: Code) {
+        self.code.extend(code.into_iter());
+        self.modules.insert(module.module_decl.name.clone(), module);
+    }
+
+    pub fn add_batched_code(&mut self, code: Code, code_dir: CodeDir) {
+        self.code.extend(code.into_iter());
+        self.code_dir.extend(code_dir.into_iter());
+    }
+
+    pub fn add_batched_ops(&mut self, op_dir: OpDir) {
+        self.op_dir.extend(op_dir.into_iter());
+    }
+
+    pub fn add_user_code(&mut self, name: ClauseName, arity: usize, code: Code) -> EvalSession {
+        let p = self.code_size();
+
+        self.code.extend(code.into_iter());
+
+        let idx = self.code_dir.entry((name, arity)).or_insert(CodeIndex::default());
+        idx.set(IndexPtr::Index(p), ClauseName::BuiltIn("user"));
+
+        EvalSession::EntrySuccess
+    }
+
+    pub fn submit_query(&mut self, code: Code, _vars: AllocVarDict) -> EvalSession {
+        self.code.extend(code.into_iter());
+        EvalSession::EntrySuccess
+    }
+
+    pub fn use_module_in_toplevel(&mut self, name: ClauseName) -> EvalSession {
+        let submodule = match self.modules.get(&name) {
+            Some(submodule) => (submodule.code_dir.clone(), submodule.op_dir.clone()),
+            None => return EvalSession::from(SessionError::ModuleNotFound)
+        };
+
+        self.code_dir.extend(submodule.0);
+        self.op_dir.extend(submodule.1);
+
+        EvalSession::EntrySuccess
+    }
+
+    pub fn use_qualified_module_in_toplevel(&mut self, name: ClauseName, exports: Vec<PredicateKey>)
+                                            -> EvalSession
+    {
+        let submodule = match self.modules.get(&name) {
+            Some(submodule) => (submodule.code_dir.clone(), submodule.op_dir.clone()),
+            None => return EvalSession::from(SessionError::ModuleNotFound)
+        };
+
+        for key in &exports {
+            if let Some(idx) = submodule.0.get(key) {
+                self.code_dir.insert(key.clone(), idx.clone());
+            }
+        }
+
+        EvalSession::EntrySuccess
+    }
+}
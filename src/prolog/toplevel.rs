@@ -0,0 +1,205 @@
+use prolog_parser::ast::{ClauseName, Term};
+
+use prolog::machine::{AtomTable, MachineCodeIndices, MachineFlags};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Read;
+use std::rc::Rc;
+
+pub type PredicateKey = (ClauseName, usize);
+
+#[derive(Debug)]
+pub enum ParserError {
+    ExpectedRel,
+    InvalidModuleDecl
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    NamelessEntry,
+    ImpermissibleEntry(String),
+    ModuleNotFound,
+    /// The key named by a `:- extern(name/arity)` declaration has no
+    /// callback registered for it via `Machine::register_extern`.
+    ExternNotFound(ClauseName, usize),
+    ParserError(ParserError)
+}
+
+impl From<ParserError> for SessionError {
+    fn from(e: ParserError) -> Self {
+        SessionError::ParserError(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalSession {
+    EntrySuccess,
+    Error(SessionError)
+}
+
+impl From<SessionError> for EvalSession {
+    fn from(e: SessionError) -> Self {
+        EvalSession::Error(e)
+    }
+}
+
+impl From<ParserError> for EvalSession {
+    fn from(e: ParserError) -> Self {
+        EvalSession::Error(SessionError::from(e))
+    }
+}
+
+#[macro_export]
+macro_rules! try_eval_session {
+    ($e:expr) => {
+        match $e {
+            Ok(val) => val,
+            Err(e) => return EvalSession::from(e)
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! clause_name {
+    ($name:expr) => {
+        ClauseName::BuiltIn(Box::leak($name.to_string().into_boxed_str()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModuleDecl {
+    pub name: ClauseName,
+    pub exports: Vec<PredicateKey>
+}
+
+#[derive(Debug, Clone)]
+pub struct OpDecl {
+    pub name: ClauseName,
+    pub priority: usize,
+    pub spec: &'static str
+}
+
+impl OpDecl {
+    pub fn submit(&self, _module_name: ClauseName, op_dir: &mut ::prolog::machine::OpDir)
+                  -> Result<(), SessionError>
+    {
+        op_dir.insert((self.name.clone(), 0), self.clone());
+        Ok(())
+    }
+}
+
+pub enum Declaration {
+    NonCountedBacktracking(ClauseName, usize),
+    /// `:- extern(name/arity)`: bind this predicate key to a native
+    /// callback registered on the machine via `Machine::register_extern`,
+    /// rather than requiring compiled clauses for it.
+    Extern(ClauseName, usize),
+    Op(OpDecl),
+    UseModule(ClauseName),
+    UseQualifiedModule(ClauseName, Vec<PredicateKey>),
+    Module(ModuleDecl)
+}
+
+/// A single clause in a consulted file, with enough of its head resolved
+/// up front (name, arity) that the rest of the pipeline never has to dig
+/// back into the parsed term for it.
+pub struct FactClause {
+    pub name: ClauseName,
+    pub arity: usize,
+    pub term: Term
+}
+
+pub struct RuleClause {
+    pub name: ClauseName,
+    pub arity: usize,
+    pub head: Term,
+    pub body: Vec<QueryTerm>
+}
+
+pub struct QueryTerm {
+    pub term: Term
+}
+
+pub struct Predicate(pub Vec<TopLevel>);
+
+pub enum ClauseType {
+    Named(ClauseName, usize),
+    Op(ClauseName, usize)
+}
+
+impl ClauseType {
+    pub fn from(name: ClauseName, arity: usize, _spec: Option<()>) -> ClauseType {
+        ClauseType::Named(name, arity)
+    }
+}
+
+pub enum TopLevel {
+    Declaration(Declaration),
+    Query(Vec<QueryTerm>),
+    Predicate(Predicate),
+    Fact(FactClause),
+    Rule(RuleClause)
+}
+
+impl TopLevel {
+    pub fn name(&self) -> Option<ClauseName> {
+        match self {
+            &TopLevel::Fact(ref fact) => Some(fact.name.clone()),
+            &TopLevel::Rule(ref rule) => Some(rule.name.clone()),
+            &TopLevel::Predicate(ref clauses) => clauses.0.first().and_then(|cl| cl.name()),
+            _ => None
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        match self {
+            &TopLevel::Fact(ref fact) => fact.arity,
+            &TopLevel::Rule(ref rule) => rule.arity,
+            &TopLevel::Predicate(ref clauses) => clauses.0.first().map_or(0, |cl| cl.arity()),
+            _ => 0
+        }
+    }
+}
+
+pub enum TopLevelPacket {
+    Query(Vec<QueryTerm>, Vec<TopLevel>),
+    Decl(TopLevel, Vec<TopLevel>)
+}
+
+pub fn parse_term(_term: Term, _indices: MachineCodeIndices) -> Result<TopLevelPacket, ParserError> {
+    Err(ParserError::ExpectedRel)
+}
+
+pub struct TopLevelWorker;
+
+impl TopLevelWorker {
+    pub fn new(_buffer: &[u8], _atom_tbl: Rc<RefCell<AtomTable>>, _flags: MachineFlags,
+              _indices: MachineCodeIndices)
+              -> Self
+    {
+        TopLevelWorker
+    }
+
+    pub fn parse_code(&mut self) -> Result<TopLevelPacket, ParserError> {
+        Err(ParserError::ExpectedRel)
+    }
+}
+
+pub struct TopLevelBatchWorker<R> {
+    src: R,
+    atom_tbl: Rc<RefCell<AtomTable>>,
+    flags: MachineFlags,
+    pub results: Vec<(Predicate, VecDeque<TopLevel>)>
+}
+
+impl<R: Read> TopLevelBatchWorker<R> {
+    pub fn new(src: R, atom_tbl: Rc<RefCell<AtomTable>>, flags: MachineFlags) -> Self {
+        TopLevelBatchWorker { src, atom_tbl, flags, results: Vec::new() }
+    }
+
+    pub fn consume(&mut self, _indices: &mut MachineCodeIndices) -> Result<Option<Declaration>, SessionError> {
+        let _ = (&self.src, &self.atom_tbl, &self.flags);
+        Ok(None)
+    }
+}